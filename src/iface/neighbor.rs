@@ -1,30 +1,49 @@
 // Heads up! Before working on this file you should read, at least,
 // the parts of RFC 1122 that discuss ARP.
 
+use core::slice;
+#[cfg(any(feature = "std", feature = "alloc"))]
+use alloc::collections::btree_map;
+
 use managed::ManagedMap;
 
-use wire::{EthernetAddress, IpAddress};
+use time::{Duration, Instant};
+use wire::{HardwareAddress, IpAddress};
 
 /// A cached neighbor.
 ///
 /// A neighbor mapping translates from a protocol address to a hardware address,
 /// and contains the timestamp past which the mapping should be discarded.
+/// A `None` timestamp marks a static entry, pinned by the user, that never
+/// expires.
 #[derive(Debug, Clone, Copy)]
 pub struct Neighbor {
-    hardware_addr: EthernetAddress,
-    expires_at:    u64,
+    hardware_addr: HardwareAddress,
+    expires_at:    Option<Instant>,
+    last_used:     Instant,
 }
 
 /// An answer to a neighbor cache lookup.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum Answer {
     /// The neighbor address is in the cache and not expired.
-    Found(EthernetAddress),
+    Found(HardwareAddress),
     /// The neighbor address is not in the cache, or has expired.
     NotFound,
-    /// The neighbor address is not in the cache, or has expired,
-    /// and a lookup has been made recently.
-    Hushed
+    /// The neighbor address is not in the cache, or has expired, and a
+    /// discovery request has been made recently, so another one should not
+    /// be sent yet.
+    RateLimited,
+}
+
+impl Answer {
+    /// Query whether the lookup found a usable hardware address.
+    pub(crate) fn found(&self) -> bool {
+        match *self {
+            Answer::Found(_) => true,
+            Answer::NotFound | Answer::RateLimited => false,
+        }
+    }
 }
 
 /// A neighbor cache backed by a map.
@@ -46,16 +65,18 @@ pub(crate) enum Answer {
 /// ```
 #[derive(Debug)]
 pub struct Cache<'a> {
-    storage:      ManagedMap<'a, IpAddress, Neighbor>,
-    hushed_until: u64,
+    storage:        ManagedMap<'a, IpAddress, Neighbor>,
+    silent_time:    Duration,
+    entry_lifetime: Duration,
+    hushed_until:   Instant,
 }
 
 impl<'a> Cache<'a> {
-    /// Minimum delay between discovery requests, in milliseconds.
-    pub(crate) const SILENT_TIME: u64 = 1_000;
+    /// Default minimum delay between discovery requests.
+    pub(crate) const DEFAULT_SILENT_TIME: Duration = Duration::from_millis(1_000);
 
-    /// Neighbor entry lifetime, in milliseconds.
-    pub(crate) const ENTRY_LIFETIME: u64 = 60_000;
+    /// Default neighbor entry lifetime.
+    pub(crate) const DEFAULT_ENTRY_LIFETIME: Duration = Duration::from_millis(60_000);
 
     /// Create a cache. The backing storage is cleared upon creation.
     ///
@@ -66,17 +87,84 @@ impl<'a> Cache<'a> {
         let mut storage = storage.into();
         storage.clear();
 
-        Cache { storage, hushed_until: 0 }
+        Cache {
+            storage,
+            silent_time:    Self::DEFAULT_SILENT_TIME,
+            entry_lifetime: Self::DEFAULT_ENTRY_LIFETIME,
+            hushed_until:   Instant::from_millis(0),
+        }
+    }
+
+    /// Set the minimum delay between discovery requests for the same
+    /// protocol address.
+    ///
+    /// On slow or congested links, the default may cause the interface to
+    /// flood the network with ARP/NDP requests; raising it backs off
+    /// discovery accordingly.
+    pub fn set_silent_time(&mut self, time: Duration) {
+        self.silent_time = time;
+    }
+
+    /// Set how long a discovered neighbor entry is kept before it must be
+    /// rediscovered.
+    pub fn set_entry_lifetime(&mut self, lifetime: Duration) {
+        self.entry_lifetime = lifetime;
     }
 
-    pub(crate) fn fill(&mut self, protocol_addr: IpAddress, hardware_addr: EthernetAddress,
-                       timestamp: u64) {
+    /// Add a static neighbor mapping that never expires.
+    ///
+    /// This lets an application pin a gateway or other trusted neighbor's
+    /// hardware address so that it never expires and is never replaced by
+    /// a discovered mapping, which is useful on networks where ARP/NDP
+    /// spoofing of that entry would otherwise be possible.
+    ///
+    /// When a fixed-size storage is full, timed entries are evicted before
+    /// static ones; a static entry is evicted only when every entry in the
+    /// storage is static.
+    pub fn add_static(&mut self, protocol_addr: IpAddress, hardware_addr: HardwareAddress,
+                      timestamp: Instant) {
+        self.fill_impl(protocol_addr, hardware_addr, None, timestamp);
+    }
+
+    /// Remove a neighbor from the cache, if it is present.
+    pub fn remove(&mut self, protocol_addr: IpAddress) {
+        self.storage.remove(&protocol_addr);
+    }
+
+    /// Remove all entries from the cache.
+    pub fn flush(&mut self) {
+        self.storage.clear();
+    }
+
+    /// Iterate over the entries currently in the cache, as
+    /// `(protocol_addr, hardware_addr, expires_at)` triples. A static
+    /// entry's `expires_at` is `None`.
+    pub fn entries<'s>(&'s self) -> Entries<'s> {
+        match self.storage {
+            ManagedMap::Borrowed(ref pairs) =>
+                Entries::Borrowed(pairs.iter()),
+            #[cfg(any(feature = "std", feature = "alloc"))]
+            ManagedMap::Owned(ref map) =>
+                Entries::Owned(map.iter()),
+        }
+    }
+
+    pub(crate) fn fill(&mut self, protocol_addr: IpAddress, hardware_addr: HardwareAddress,
+                       timestamp: Instant) {
+        // A static entry is never replaced by a discovered mapping.
+        if let Some(&Neighbor { expires_at: None, .. }) = self.storage.get(&protocol_addr) {
+            return
+        }
+        let expires_at = timestamp + self.entry_lifetime;
+        self.fill_impl(protocol_addr, hardware_addr, Some(expires_at), timestamp);
+    }
+
+    fn fill_impl(&mut self, protocol_addr: IpAddress, hardware_addr: HardwareAddress,
+                 expires_at: Option<Instant>, timestamp: Instant) {
         debug_assert!(protocol_addr.is_unicast());
         debug_assert!(hardware_addr.is_unicast());
 
-        let neighbor = Neighbor {
-            expires_at: timestamp + Self::ENTRY_LIFETIME, hardware_addr
-        };
+        let neighbor = Neighbor { expires_at, hardware_addr, last_used: timestamp };
         match self.storage.insert(protocol_addr, neighbor) {
             Ok(Some(old_neighbor)) => {
                 if old_neighbor.hardware_addr != hardware_addr {
@@ -92,15 +180,29 @@ impl<'a> Cache<'a> {
                 // is full, and we need to evict an entry.
                 let old_protocol_addr = match self.storage {
                     ManagedMap::Borrowed(ref mut pairs) => {
-                        pairs
-                            .iter()
-                            .min_by_key(|pair_opt| {
-                                let (_protocol_addr, neighbor) = pair_opt.unwrap();
-                                neighbor.expires_at
-                            })
-                            .expect("empty neighbor cache storage") // unwraps min_by_key
-                            .unwrap() // unwraps pair
-                            .0
+                        // Evict an already-expired entry first, if there is one. Otherwise,
+                        // evict the least-recently-used timed entry, keeping static entries
+                        // (`expires_at == None`) around as long as any timed entry remains;
+                        // only evict a static entry as a last resort.
+                        let filled = || pairs.iter().filter_map(|pair_opt| pair_opt.as_ref());
+
+                        let lru_expired = || filled()
+                            .filter(|&&(_, neighbor)|
+                                neighbor.expires_at.map_or(false, |expires_at| expires_at < timestamp))
+                            .min_by_key(|&&(_, neighbor)| neighbor.last_used)
+                            .map(|&(protocol_addr, _)| protocol_addr);
+
+                        let lru_timed = || filled()
+                            .filter(|&&(_, neighbor)| neighbor.expires_at.is_some())
+                            .min_by_key(|&&(_, neighbor)| neighbor.last_used)
+                            .map(|&(protocol_addr, _)| protocol_addr);
+
+                        let lru_any = || filled()
+                            .min_by_key(|&&(_, neighbor)| neighbor.last_used)
+                            .expect("empty neighbor cache storage")
+                            .0;
+
+                        lru_expired().or_else(lru_timed).unwrap_or_else(lru_any)
                     }
                     // Owned maps can extend themselves.
                     #[cfg(any(feature = "std", feature = "alloc"))]
@@ -123,15 +225,16 @@ impl<'a> Cache<'a> {
         }
     }
 
-    pub(crate) fn lookup_pure(&self, protocol_addr: &IpAddress, timestamp: u64) ->
-                             Option<EthernetAddress> {
+    pub(crate) fn lookup_pure(&mut self, protocol_addr: &IpAddress, broadcast_addr: HardwareAddress,
+                              timestamp: Instant) -> Option<HardwareAddress> {
         if protocol_addr.is_broadcast() {
-            return Some(EthernetAddress::BROADCAST)
+            return Some(broadcast_addr)
         }
 
-        match self.storage.get(protocol_addr) {
-            Some(&Neighbor { expires_at, hardware_addr }) => {
-                if timestamp < expires_at {
+        match self.storage.get_mut(protocol_addr) {
+            Some(&mut Neighbor { expires_at, hardware_addr, ref mut last_used }) => {
+                if expires_at.map_or(true, |expires_at| timestamp < expires_at) {
+                    *last_used = timestamp;
                     return Some(hardware_addr)
                 }
             }
@@ -141,29 +244,75 @@ impl<'a> Cache<'a> {
         None
     }
 
-    pub(crate) fn lookup(&mut self, protocol_addr: &IpAddress, timestamp: u64) -> Answer {
-        match self.lookup_pure(protocol_addr, timestamp) {
+    pub(crate) fn lookup(&mut self, protocol_addr: &IpAddress, broadcast_addr: HardwareAddress,
+                        timestamp: Instant) -> Answer {
+        let answer = match self.lookup_pure(protocol_addr, broadcast_addr, timestamp) {
             Some(hardware_addr) =>
                 Answer::Found(hardware_addr),
-            None if timestamp < self.hushed_until =>
-                Answer::Hushed,
+            None if timestamp < self.hushed_until() =>
+                Answer::RateLimited,
             None => {
-                self.hushed_until = timestamp + Self::SILENT_TIME;
+                self.hushed_until = timestamp + self.silent_time;
                 Answer::NotFound
             }
+        };
+        if !answer.found() {
+            net_trace!("lookup {} => {:?}, hushed until {}",
+                       protocol_addr, answer, self.hushed_until());
+        }
+        answer
+    }
+
+    /// Return the instant at which the discovery rate limit for addresses
+    /// not currently in the cache lifts, letting the interface schedule its
+    /// next poll precisely instead of busy-retrying.
+    pub(crate) fn hushed_until(&self) -> Instant {
+        self.hushed_until
+    }
+}
+
+/// An iterator over the entries of a [`Cache`](struct.Cache.html).
+///
+/// This value is returned by [`Cache::entries`](struct.Cache.html#method.entries).
+pub enum Entries<'a> {
+    #[doc(hidden)]
+    Borrowed(slice::Iter<'a, Option<(IpAddress, Neighbor)>>),
+    #[doc(hidden)]
+    #[cfg(any(feature = "std", feature = "alloc"))]
+    Owned(btree_map::Iter<'a, IpAddress, Neighbor>),
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = (IpAddress, HardwareAddress, Option<Instant>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match *self {
+            Entries::Borrowed(ref mut iter) => {
+                while let Some(pair_opt) = iter.next() {
+                    if let Some((protocol_addr, neighbor)) = *pair_opt {
+                        return Some((protocol_addr, neighbor.hardware_addr, neighbor.expires_at))
+                    }
+                }
+                None
+            }
+            #[cfg(any(feature = "std", feature = "alloc"))]
+            Entries::Owned(ref mut iter) => {
+                iter.next().map(|(&protocol_addr, neighbor)|
+                    (protocol_addr, neighbor.hardware_addr, neighbor.expires_at))
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use wire::Ipv4Address;
+    use wire::{EthernetAddress, Ipv4Address};
     use super::*;
 
-    const HADDR_A: EthernetAddress = EthernetAddress([0, 0, 0, 0, 0, 1]);
-    const HADDR_B: EthernetAddress = EthernetAddress([0, 0, 0, 0, 0, 2]);
-    const HADDR_C: EthernetAddress = EthernetAddress([0, 0, 0, 0, 0, 3]);
-    const HADDR_D: EthernetAddress = EthernetAddress([0, 0, 0, 0, 0, 4]);
+    const HADDR_A: HardwareAddress = HardwareAddress::Ethernet(EthernetAddress([0, 0, 0, 0, 0, 1]));
+    const HADDR_B: HardwareAddress = HardwareAddress::Ethernet(EthernetAddress([0, 0, 0, 0, 0, 2]));
+    const HADDR_C: HardwareAddress = HardwareAddress::Ethernet(EthernetAddress([0, 0, 0, 0, 0, 3]));
+    const HADDR_D: HardwareAddress = HardwareAddress::Ethernet(EthernetAddress([0, 0, 0, 0, 0, 4]));
 
     const PADDR_A: IpAddress = IpAddress::Ipv4(Ipv4Address([1, 0, 0, 1]));
     const PADDR_B: IpAddress = IpAddress::Ipv4(Ipv4Address([1, 0, 0, 2]));
@@ -175,16 +324,17 @@ mod test {
         let mut cache_storage = [Default::default(); 3];
         let mut cache = Cache::new(&mut cache_storage[..]);
 
-        assert_eq!(cache.lookup_pure(&PADDR_A, 0), None);
-        assert_eq!(cache.lookup_pure(&PADDR_B, 0), None);
+        assert_eq!(cache.lookup_pure(&PADDR_A, HardwareAddress::BROADCAST, Instant::from_millis(0)), None);
+        assert_eq!(cache.lookup_pure(&PADDR_B, HardwareAddress::BROADCAST, Instant::from_millis(0)), None);
 
-        cache.fill(PADDR_A, HADDR_A, 0);
-        assert_eq!(cache.lookup_pure(&PADDR_A, 0), Some(HADDR_A));
-        assert_eq!(cache.lookup_pure(&PADDR_B, 0), None);
-        assert_eq!(cache.lookup_pure(&PADDR_A, 2 * Cache::ENTRY_LIFETIME), None);
+        cache.fill(PADDR_A, HADDR_A, Instant::from_millis(0));
+        assert_eq!(cache.lookup_pure(&PADDR_A, HardwareAddress::BROADCAST, Instant::from_millis(0)), Some(HADDR_A));
+        assert_eq!(cache.lookup_pure(&PADDR_B, HardwareAddress::BROADCAST, Instant::from_millis(0)), None);
+        assert_eq!(cache.lookup_pure(&PADDR_A, HardwareAddress::BROADCAST,
+                                      Instant::from_millis(0) + Cache::DEFAULT_ENTRY_LIFETIME * 2), None);
 
-        cache.fill(PADDR_A, HADDR_A, 0);
-        assert_eq!(cache.lookup_pure(&PADDR_B, 0), None);
+        cache.fill(PADDR_A, HADDR_A, Instant::from_millis(0));
+        assert_eq!(cache.lookup_pure(&PADDR_B, HardwareAddress::BROADCAST, Instant::from_millis(0)), None);
     }
 
     #[test]
@@ -192,9 +342,10 @@ mod test {
         let mut cache_storage = [Default::default(); 3];
         let mut cache = Cache::new(&mut cache_storage[..]);
 
-        cache.fill(PADDR_A, HADDR_A, 0);
-        assert_eq!(cache.lookup_pure(&PADDR_A, 0), Some(HADDR_A));
-        assert_eq!(cache.lookup_pure(&PADDR_A, 2 * Cache::ENTRY_LIFETIME), None);
+        cache.fill(PADDR_A, HADDR_A, Instant::from_millis(0));
+        assert_eq!(cache.lookup_pure(&PADDR_A, HardwareAddress::BROADCAST, Instant::from_millis(0)), Some(HADDR_A));
+        assert_eq!(cache.lookup_pure(&PADDR_A, HardwareAddress::BROADCAST,
+                                      Instant::from_millis(0) + Cache::DEFAULT_ENTRY_LIFETIME * 2), None);
     }
 
     #[test]
@@ -202,36 +353,81 @@ mod test {
         let mut cache_storage = [Default::default(); 3];
         let mut cache = Cache::new(&mut cache_storage[..]);
 
-        cache.fill(PADDR_A, HADDR_A, 0);
-        assert_eq!(cache.lookup_pure(&PADDR_A, 0), Some(HADDR_A));
-        cache.fill(PADDR_A, HADDR_B, 0);
-        assert_eq!(cache.lookup_pure(&PADDR_A, 0), Some(HADDR_B));
+        cache.fill(PADDR_A, HADDR_A, Instant::from_millis(0));
+        assert_eq!(cache.lookup_pure(&PADDR_A, HardwareAddress::BROADCAST, Instant::from_millis(0)), Some(HADDR_A));
+        cache.fill(PADDR_A, HADDR_B, Instant::from_millis(0));
+        assert_eq!(cache.lookup_pure(&PADDR_A, HardwareAddress::BROADCAST, Instant::from_millis(0)), Some(HADDR_B));
     }
 
     #[test]
-    fn test_evict() {
+    fn test_evict_lru() {
         let mut cache_storage = [Default::default(); 3];
         let mut cache = Cache::new(&mut cache_storage[..]);
 
-        cache.fill(PADDR_A, HADDR_A, 100);
-        cache.fill(PADDR_B, HADDR_B, 50);
-        cache.fill(PADDR_C, HADDR_C, 200);
-        assert_eq!(cache.lookup_pure(&PADDR_B, 1000), Some(HADDR_B));
-        assert_eq!(cache.lookup_pure(&PADDR_D, 1000), None);
+        cache.fill(PADDR_A, HADDR_A, Instant::from_millis(100));
+        cache.fill(PADDR_B, HADDR_B, Instant::from_millis(50));
+        cache.fill(PADDR_C, HADDR_C, Instant::from_millis(200));
+
+        // Touch B, making it more recently used than A, even though B has an earlier
+        // expiry than A. The eviction that follows should still spare B.
+        assert_eq!(cache.lookup_pure(&PADDR_B, HardwareAddress::BROADCAST, Instant::from_millis(1000)), Some(HADDR_B));
+        assert_eq!(cache.lookup_pure(&PADDR_D, HardwareAddress::BROADCAST, Instant::from_millis(1000)), None);
 
-        cache.fill(PADDR_D, HADDR_D, 300);
-        assert_eq!(cache.lookup_pure(&PADDR_B, 1000), None);
-        assert_eq!(cache.lookup_pure(&PADDR_D, 1000), Some(HADDR_D));
+        cache.fill(PADDR_D, HADDR_D, Instant::from_millis(300));
+        assert_eq!(cache.lookup_pure(&PADDR_A, HardwareAddress::BROADCAST, Instant::from_millis(1000)), None);
+        assert_eq!(cache.lookup_pure(&PADDR_B, HardwareAddress::BROADCAST, Instant::from_millis(1000)), Some(HADDR_B));
+        assert_eq!(cache.lookup_pure(&PADDR_D, HardwareAddress::BROADCAST, Instant::from_millis(1000)), Some(HADDR_D));
     }
 
     #[test]
-    fn test_hush() {
+    fn test_evict_expired_first() {
         let mut cache_storage = [Default::default(); 3];
         let mut cache = Cache::new(&mut cache_storage[..]);
 
-        assert_eq!(cache.lookup(&PADDR_A, 0), Answer::NotFound);
-        assert_eq!(cache.lookup(&PADDR_A, 100), Answer::Hushed);
-        assert_eq!(cache.lookup(&PADDR_A, 2000), Answer::NotFound);
+        cache.fill(PADDR_A, HADDR_A, Instant::from_millis(0));
+        cache.fill(PADDR_B, HADDR_B, Instant::from_millis(1000));
+        cache.fill(PADDR_C, HADDR_C, Instant::from_millis(2000));
+
+        // A has already expired by this point, while B and C, freshly touched, have
+        // not. The already-expired A should be evicted to make room for D.
+        let now = Instant::from_millis(60_500);
+        assert_eq!(cache.lookup_pure(&PADDR_A, HardwareAddress::BROADCAST, now), None);
+        assert_eq!(cache.lookup_pure(&PADDR_B, HardwareAddress::BROADCAST, now), Some(HADDR_B));
+        assert_eq!(cache.lookup_pure(&PADDR_C, HardwareAddress::BROADCAST, now), Some(HADDR_C));
+
+        cache.fill(PADDR_D, HADDR_D, now);
+        assert_eq!(cache.lookup_pure(&PADDR_A, HardwareAddress::BROADCAST, now), None);
+        assert_eq!(cache.lookup_pure(&PADDR_B, HardwareAddress::BROADCAST, now), Some(HADDR_B));
+        assert_eq!(cache.lookup_pure(&PADDR_C, HardwareAddress::BROADCAST, now), Some(HADDR_C));
+        assert_eq!(cache.lookup_pure(&PADDR_D, HardwareAddress::BROADCAST, now), Some(HADDR_D));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut cache_storage = [Default::default(); 3];
+        let mut cache = Cache::new(&mut cache_storage[..]);
+
+        assert_eq!(cache.lookup(&PADDR_A, HardwareAddress::BROADCAST, Instant::from_millis(0)), Answer::NotFound);
+        assert_eq!(cache.lookup(&PADDR_A, HardwareAddress::BROADCAST, Instant::from_millis(100)), Answer::RateLimited);
+        assert_eq!(cache.lookup(&PADDR_A, HardwareAddress::BROADCAST, Instant::from_millis(2000)), Answer::NotFound);
+    }
+
+    #[test]
+    fn test_configurable_silent_time_and_entry_lifetime() {
+        let mut cache_storage = [Default::default(); 3];
+        let mut cache = Cache::new(&mut cache_storage[..]);
+        cache.set_silent_time(Duration::from_millis(5_000));
+        cache.set_entry_lifetime(Duration::from_millis(500));
+
+        assert_eq!(cache.lookup(&PADDR_A, HardwareAddress::BROADCAST, Instant::from_millis(0)), Answer::NotFound);
+        assert_eq!(cache.hushed_until(), Instant::from_millis(5_000));
+        assert_eq!(cache.lookup(&PADDR_A, HardwareAddress::BROADCAST, Instant::from_millis(4_999)), Answer::RateLimited);
+        assert_eq!(cache.lookup(&PADDR_A, HardwareAddress::BROADCAST, Instant::from_millis(5_000)), Answer::NotFound);
+
+        cache.fill(PADDR_B, HADDR_B, Instant::from_millis(0));
+        assert_eq!(cache.lookup_pure(&PADDR_B, HardwareAddress::BROADCAST, Instant::from_millis(0)), Some(HADDR_B));
+        assert!(Answer::Found(HADDR_B).found());
+        assert_eq!(cache.lookup_pure(&PADDR_B, HardwareAddress::BROADCAST, Instant::from_millis(500)), None);
     }
 }
 
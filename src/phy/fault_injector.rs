@@ -0,0 +1,360 @@
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::cmp;
+
+use Result;
+use time::Instant;
+use phy::{self, DeviceCapabilities, Device};
+
+/// An event reported to a `FaultInjector`'s trace writer, if any, whenever
+/// it drops or corrupts a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultEvent {
+    /// A received frame was dropped.
+    RxDropped,
+    /// A received frame had a single bit flipped.
+    RxCorrupted,
+    /// A transmitted frame was dropped.
+    TxDropped,
+    /// A transmitted frame had a single bit flipped.
+    TxCorrupted,
+}
+
+type Writer = RefCell<Option<Box<dyn FnMut(Instant, FaultEvent)>>>;
+
+// We use a small and fast PRNG (xorshift) instead of anything from `rand`
+// so that fault injection stays reproducible across platforms without an
+// external dependency, given the same seed.
+#[derive(Debug, Clone, Copy)]
+struct Rng {
+    state: u32
+}
+
+impl Rng {
+    fn new(seed: u32) -> Rng {
+        Rng { state: if seed == 0 { 0x2545F491 } else { seed } }
+    }
+
+    fn next(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /// Return `true` with probability `chance / 255`.
+    fn chance(&mut self, chance: u8) -> bool {
+        (self.next() % 255) < chance as u32
+    }
+}
+
+/// A token bucket rate limiter keyed off the `consume` timestamp.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    rate:        u64, // tokens (packets) per second; 0 means unlimited
+    tokens:      u64,
+    last_filled: Instant,
+}
+
+impl Bucket {
+    fn unlimited() -> Bucket {
+        Bucket { rate: 0, tokens: 0, last_filled: Instant::from_millis(0) }
+    }
+
+    fn set_rate(&mut self, rate: u64) {
+        self.rate = rate;
+        self.tokens = rate;
+    }
+
+    /// Returns `true` if a packet may pass at `timestamp`, consuming a token.
+    fn take(&mut self, timestamp: Instant) -> bool {
+        if self.rate == 0 { return true }
+
+        let elapsed = if timestamp > self.last_filled {
+            (timestamp - self.last_filled).total_millis()
+        } else {
+            0
+        };
+        let refill = elapsed * self.rate / 1000;
+        if refill > 0 {
+            self.tokens = cmp::min(self.rate, self.tokens + refill);
+            self.last_filled = timestamp;
+        }
+
+        if self.tokens > 0 {
+            self.tokens -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct State {
+    rng:             Rng,
+    drop_chance:     u8,
+    corrupt_chance:  u8,
+    max_size:        Option<usize>,
+    rx_bucket:       Bucket,
+    tx_bucket:       Bucket,
+}
+
+impl State {
+    fn maybe_drop(&mut self) -> bool {
+        self.rng.chance(self.drop_chance)
+    }
+
+    fn maybe_corrupt(&mut self, buffer: &mut [u8]) -> bool {
+        if buffer.is_empty() || !self.rng.chance(self.corrupt_chance) {
+            return false
+        }
+        let index = (self.rng.next() as usize) % buffer.len();
+        let flip  = 1 << (self.rng.next() % 8);
+        buffer[index] ^= flip;
+        true
+    }
+
+    fn truncate<'b>(&self, buffer: &'b mut [u8]) -> &'b mut [u8] {
+        match self.max_size {
+            Some(max_size) if buffer.len() > max_size => &mut buffer[..max_size],
+            _ => buffer
+        }
+    }
+}
+
+/// A fault-injection device that wraps another device, letting tests
+/// exercise retransmission, reassembly, and congestion paths without a
+/// real lossy link.
+///
+/// All randomness is derived from a seed given at construction time, so
+/// a given configuration reproduces the exact same sequence of faults.
+pub struct FaultInjector<D: for<'a> Device<'a>> {
+    inner:  D,
+    state:  Rc<RefCell<State>>,
+    writer: Rc<Writer>,
+}
+
+impl<D: for<'a> Device<'a>> FaultInjector<D> {
+    /// Create a fault injector device, seeding its PRNG with `seed`.
+    pub fn new(inner: D, seed: u32) -> FaultInjector<D> {
+        let state = State {
+            rng:            Rng::new(seed),
+            drop_chance:    0,
+            corrupt_chance: 0,
+            max_size:       None,
+            rx_bucket:      Bucket::unlimited(),
+            tx_bucket:      Bucket::unlimited(),
+        };
+        FaultInjector {
+            inner,
+            state:  Rc::new(RefCell::new(state)),
+            writer: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    /// Set a closure to be called, with the timestamp of the offending
+    /// frame, whenever a frame is dropped or corrupted. There is none by
+    /// default.
+    pub fn set_trace_writer<W: FnMut(Instant, FaultEvent) + 'static>(&mut self, writer: W) {
+        *self.writer.borrow_mut() = Some(Box::new(writer));
+    }
+
+    /// Return the underlying device, consuming the fault injector.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Set the chance of a received or transmitted packet being dropped,
+    /// out of 255.
+    pub fn set_drop_chance(&mut self, chance: u8) {
+        self.state.borrow_mut().drop_chance = chance;
+    }
+
+    /// Set the chance of a received or transmitted packet having a single
+    /// bit flipped, out of 255.
+    pub fn set_corrupt_chance(&mut self, chance: u8) {
+        self.state.borrow_mut().corrupt_chance = chance;
+    }
+
+    /// Set the maximum size a packet may have before it is truncated.
+    pub fn set_max_packet_size(&mut self, size: usize) {
+        self.state.borrow_mut().max_size = Some(size);
+    }
+
+    /// Set the maximum rate, in packets per second, at which packets may
+    /// be transmitted. A rate of `0` means unlimited.
+    pub fn set_max_tx_rate(&mut self, rate: u64) {
+        self.state.borrow_mut().tx_bucket.set_rate(rate);
+    }
+
+    /// Set the maximum rate, in packets per second, at which packets may
+    /// be received. A rate of `0` means unlimited.
+    pub fn set_max_rx_rate(&mut self, rate: u64) {
+        self.state.borrow_mut().rx_bucket.set_rate(rate);
+    }
+}
+
+impl<'a, D> Device<'a> for FaultInjector<D>
+    where D: for<'b> Device<'b>,
+{
+    type RxToken = RxToken<<D as Device<'a>>::RxToken>;
+    type TxToken = TxToken<<D as Device<'a>>::TxToken>;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = self.inner.capabilities();
+        if let Some(max_size) = self.state.borrow().max_size {
+            caps.max_transmission_unit = cmp::min(caps.max_transmission_unit, max_size);
+        }
+        caps
+    }
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let &mut Self { ref mut inner, ref state, ref writer } = self;
+        inner.receive().map(|(rx_token, tx_token)| {
+            let rx = RxToken { token: rx_token, state: state.clone(), writer: writer.clone() };
+            let tx = TxToken { token: tx_token, state: state.clone(), writer: writer.clone() };
+            (rx, tx)
+        })
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        let &mut Self { ref mut inner, ref state, ref writer } = self;
+        inner.transmit().map(|tx_token| {
+            TxToken { token: tx_token, state: state.clone(), writer: writer.clone() }
+        })
+    }
+}
+
+fn trace(writer: &Writer, timestamp: Instant, event: FaultEvent) {
+    if let Some(writer) = writer.borrow_mut().as_mut() {
+        writer(timestamp, event);
+    }
+}
+
+#[doc(hidden)]
+pub struct RxToken<Rx: phy::RxToken> {
+    token:  Rx,
+    state:  Rc<RefCell<State>>,
+    writer: Rc<Writer>,
+}
+
+impl<Rx: phy::RxToken> phy::RxToken for RxToken<Rx> {
+    fn consume<R, F>(self, timestamp: Instant, f: F) -> Result<R>
+        where F: FnOnce(&[u8]) -> Result<R>
+    {
+        let Self { token, state, writer } = self;
+        token.consume(timestamp, |buffer| {
+            // The incoming `buffer` is borrowed read-only, so corruption and
+            // truncation are applied to a local copy instead of in place.
+            let mut scratch = Vec::new();
+            scratch.resize(buffer.len(), 0u8);
+            scratch.copy_from_slice(buffer);
+            let mut buffer = &mut scratch[..];
+
+            let mut state = state.borrow_mut();
+            // There is no way to make the interface skip a packet from within
+            // `consume`, so a "dropped" packet is instead corrupted beyond
+            // recognition and left for the upper layers to reject.
+            if !state.rx_bucket.take(timestamp) || state.maybe_drop() {
+                trace(&writer, timestamp, FaultEvent::RxDropped);
+                for byte in buffer.iter_mut() { *byte = 0 }
+                return f(buffer)
+            }
+            let buffer = state.truncate(buffer);
+            if state.maybe_corrupt(buffer) {
+                trace(&writer, timestamp, FaultEvent::RxCorrupted);
+            }
+            f(buffer)
+        })
+    }
+}
+
+#[doc(hidden)]
+pub struct TxToken<Tx: phy::TxToken> {
+    token:  Tx,
+    state:  Rc<RefCell<State>>,
+    writer: Rc<Writer>,
+}
+
+impl<Tx: phy::TxToken> phy::TxToken for TxToken<Tx> {
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> Result<R>
+        where F: FnOnce(&mut [u8]) -> Result<R>
+    {
+        let Self { token, state, writer } = self;
+        let len = {
+            let state = state.borrow();
+            match state.max_size {
+                Some(max_size) => cmp::min(len, max_size),
+                None => len,
+            }
+        };
+        token.consume(timestamp, len, |buffer| {
+            let result = f(buffer);
+            let mut state = state.borrow_mut();
+            // As with the receive path, there is no way to make the inner
+            // device skip a transmission from within `consume`, so a
+            // "dropped" packet is zeroed out instead of being sent intact.
+            if !state.tx_bucket.take(timestamp) || state.maybe_drop() {
+                trace(&writer, timestamp, FaultEvent::TxDropped);
+                for byte in buffer.iter_mut() { *byte = 0 }
+                return result
+            }
+            if state.maybe_corrupt(buffer) {
+                trace(&writer, timestamp, FaultEvent::TxCorrupted);
+            }
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rng_is_reproducible_given_the_same_seed() {
+        let mut a = Rng::new(0x1234);
+        let mut b = Rng::new(0x1234);
+        for _ in 0..16 {
+            assert_eq!(a.next(), b.next());
+        }
+    }
+
+    // Drive a fresh `State` through a fixed sequence of drop/corrupt
+    // decisions, returning which packets were dropped and the final
+    // contents of a repeatedly-corrupted buffer.
+    fn drive(seed: u32) -> (Vec<bool>, Vec<u8>) {
+        let mut state = State {
+            rng:            Rng::new(seed),
+            drop_chance:    255,
+            corrupt_chance: 255,
+            max_size:       None,
+            rx_bucket:      Bucket::unlimited(),
+            tx_bucket:      Bucket::unlimited(),
+        };
+        let mut drops = Vec::new();
+        let mut buffer = [0u8; 4];
+        for _ in 0..8 {
+            drops.push(state.maybe_drop());
+            state.maybe_corrupt(&mut buffer);
+        }
+        (drops, buffer.to_vec())
+    }
+
+    #[test]
+    fn test_drop_and_corrupt_sequence_is_reproducible() {
+        let (drops_a, buffer_a) = drive(0x2545F491);
+        let (drops_b, buffer_b) = drive(0x2545F491);
+        assert_eq!(drops_a, drops_b);
+        assert_eq!(buffer_a, buffer_b);
+        // A chance of 255/255 means certainty, so every packet in the run
+        // is dropped and the buffer is corrupted on every pass.
+        assert!(drops_a.iter().all(|&dropped| dropped));
+        assert_ne!(&buffer_a[..], &[0u8; 4][..]);
+    }
+}
@@ -0,0 +1,229 @@
+use alloc::rc::Rc;
+use core::cell::RefCell;
+
+use Result;
+use time::Instant;
+use phy::{self, DeviceCapabilities, Device};
+
+/// A sink for raw capture data, fed by [PcapWriter](struct.PcapWriter.html).
+///
+/// This is implemented for `Vec<u8>`, so a capture can be collected in
+/// memory, and for `std::fs::File` (behind the `std` feature), so it can
+/// be streamed straight to a `.pcap` file.
+pub trait PcapSink {
+    /// Write a slice to the sink.
+    fn write(&mut self, data: &[u8]);
+
+    /// Flush any buffered data to the underlying sink.
+    ///
+    /// The default implementation does nothing; sinks that buffer writes,
+    /// such as `std::fs::File`, should override this so that a capture
+    /// in progress survives a crash.
+    fn flush(&mut self) {}
+
+    /// Write the 24-byte global pcap file header.
+    fn global_header(&mut self, link_type: PcapLinkType) {
+        let mut buffer = [0u8; 24];
+        buffer[0..4].copy_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+        buffer[4..6].copy_from_slice(&2u16.to_le_bytes());       // version major
+        buffer[6..8].copy_from_slice(&4u16.to_le_bytes());       // version minor
+        buffer[8..12].copy_from_slice(&0i32.to_le_bytes());      // this zone
+        buffer[12..16].copy_from_slice(&0u32.to_le_bytes());     // sigfigs
+        buffer[16..20].copy_from_slice(&65535u32.to_le_bytes()); // snaplen
+        buffer[20..24].copy_from_slice(&(link_type as u32).to_le_bytes());
+        self.write(&buffer[..]);
+    }
+
+    /// Write one packet record: a 16-byte record header followed by `data`.
+    fn packet(&mut self, timestamp: Instant, data: &[u8]) {
+        let mut header = [0u8; 16];
+        header[0..4].copy_from_slice(&(timestamp.secs() as u32).to_le_bytes());
+        header[4..8].copy_from_slice(&((timestamp.millis() * 1000) as u32).to_le_bytes());
+        header[8..12].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        self.write(&header[..]);
+        self.write(data);
+        self.flush();
+    }
+}
+
+impl PcapSink for ::alloc::vec::Vec<u8> {
+    fn write(&mut self, data: &[u8]) {
+        self.extend_from_slice(data)
+    }
+}
+
+#[cfg(feature = "std")]
+impl PcapSink for ::std::fs::File {
+    fn write(&mut self, data: &[u8]) {
+        use std::io::Write;
+        self.write_all(data).expect("cannot write to pcap file");
+    }
+
+    fn flush(&mut self) {
+        ::std::io::Write::flush(self).expect("cannot flush pcap file");
+    }
+}
+
+/// Packet capture link-layer header type, as assigned by tcpdump.org.
+///
+/// See <http://www.tcpdump.org/linktypes.html> for a complete list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcapLinkType {
+    /// Ethernet, 802.3
+    Ethernet = 1,
+    /// Raw IP, with no link header whatsoever
+    Ip       = 101,
+    /// Raw IPv4
+    Ipv4     = 228,
+    /// Raw IPv6
+    Ipv6     = 229,
+}
+
+/// Which direction(s) of traffic a [PcapWriter](struct.PcapWriter.html) should capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcapMode {
+    /// Capture only received packets.
+    RxOnly,
+    /// Capture only transmitted packets.
+    TxOnly,
+    /// Capture both directions.
+    Both,
+}
+
+impl PcapMode {
+    fn capture_rx(&self) -> bool {
+        match *self {
+            PcapMode::RxOnly | PcapMode::Both => true,
+            PcapMode::TxOnly => false,
+        }
+    }
+
+    fn capture_tx(&self) -> bool {
+        match *self {
+            PcapMode::TxOnly | PcapMode::Both => true,
+            PcapMode::RxOnly => false,
+        }
+    }
+}
+
+/// A packet capture device that wraps another device, writing the frames
+/// traversing it in the direction(s) selected by its [PcapMode](enum.PcapMode.html)
+/// in libpcap format so the capture can be opened directly in Wireshark or tcpdump.
+pub struct PcapWriter<D: for<'a> Device<'a>, S: PcapSink> {
+    inner: D,
+    sink:  Rc<RefCell<S>>,
+    mode:  PcapMode,
+}
+
+impl<D: for<'a> Device<'a>, S: PcapSink> PcapWriter<D, S> {
+    /// Create a packet capture device, writing the global pcap header
+    /// to `sink` immediately.
+    pub fn new(inner: D, mut sink: S, link_type: PcapLinkType, mode: PcapMode) -> PcapWriter<D, S> {
+        sink.global_header(link_type);
+        PcapWriter { inner, sink: Rc::new(RefCell::new(sink)), mode }
+    }
+
+    /// Return the underlying device, consuming the writer.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<'a, D, S> Device<'a> for PcapWriter<D, S>
+    where D: for<'b> Device<'b>,
+          S: PcapSink + 'a,
+{
+    type RxToken = RxToken<<D as Device<'a>>::RxToken, S>;
+    type TxToken = TxToken<<D as Device<'a>>::TxToken, S>;
+
+    fn capabilities(&self) -> DeviceCapabilities { self.inner.capabilities() }
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let &mut Self { ref mut inner, ref sink, mode } = self;
+        inner.receive().map(|(rx_token, tx_token)| {
+            let rx = RxToken { token: rx_token, sink: sink.clone(), mode };
+            let tx = TxToken { token: tx_token, sink: sink.clone(), mode };
+            (rx, tx)
+        })
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        let &mut Self { ref mut inner, ref sink, mode } = self;
+        inner.transmit().map(|tx_token| {
+            TxToken { token: tx_token, sink: sink.clone(), mode }
+        })
+    }
+}
+
+#[doc(hidden)]
+pub struct RxToken<Rx: phy::RxToken, S: PcapSink> {
+    token: Rx,
+    sink:  Rc<RefCell<S>>,
+    mode:  PcapMode,
+}
+
+impl<Rx: phy::RxToken, S: PcapSink> phy::RxToken for RxToken<Rx, S> {
+    fn consume<R, F>(self, timestamp: Instant, f: F) -> Result<R>
+        where F: FnOnce(&[u8]) -> Result<R>
+    {
+        let Self { token, sink, mode } = self;
+        token.consume(timestamp, |buffer| {
+            if mode.capture_rx() {
+                sink.borrow_mut().packet(timestamp, buffer);
+            }
+            f(buffer)
+        })
+    }
+}
+
+#[doc(hidden)]
+pub struct TxToken<Tx: phy::TxToken, S: PcapSink> {
+    token: Tx,
+    sink:  Rc<RefCell<S>>,
+    mode:  PcapMode,
+}
+
+impl<Tx: phy::TxToken, S: PcapSink> phy::TxToken for TxToken<Tx, S> {
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> Result<R>
+        where F: FnOnce(&mut [u8]) -> Result<R>
+    {
+        let Self { token, sink, mode } = self;
+        token.consume(timestamp, len, |buffer| {
+            let result = f(buffer);
+            if mode.capture_tx() {
+                sink.borrow_mut().packet(timestamp, buffer);
+            }
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+    use super::*;
+
+    #[test]
+    fn test_pcap_sink_round_trip() {
+        let mut sink: Vec<u8> = Vec::new();
+        sink.global_header(PcapLinkType::Ethernet);
+        sink.packet(Instant::from_millis(1_500), &[0xaa, 0xbb, 0xcc]);
+
+        assert_eq!(&sink[0..4],   &0xa1b2c3d4u32.to_le_bytes()[..]);
+        assert_eq!(&sink[4..6],   &2u16.to_le_bytes()[..]);
+        assert_eq!(&sink[6..8],   &4u16.to_le_bytes()[..]);
+        assert_eq!(&sink[8..12],  &0i32.to_le_bytes()[..]);
+        assert_eq!(&sink[12..16], &0u32.to_le_bytes()[..]);
+        assert_eq!(&sink[16..20], &65535u32.to_le_bytes()[..]);
+        assert_eq!(&sink[20..24], &(PcapLinkType::Ethernet as u32).to_le_bytes()[..]);
+
+        let record = &sink[24..];
+        assert_eq!(&record[0..4],   &1u32.to_le_bytes()[..]);       // secs
+        assert_eq!(&record[4..8],   &500_000u32.to_le_bytes()[..]); // micros
+        assert_eq!(&record[8..12],  &3u32.to_le_bytes()[..]);       // incl_len
+        assert_eq!(&record[12..16], &3u32.to_le_bytes()[..]);       // orig_len
+        assert_eq!(&record[16..19], &[0xaa, 0xbb, 0xcc]);
+        assert_eq!(record.len(), 19);
+    }
+}
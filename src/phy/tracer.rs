@@ -1,21 +1,29 @@
+use core::cell::RefCell;
+
 use Result;
+use time::Instant;
 use wire::pretty_print::{PrettyPrint, PrettyPrinter};
 use phy::{self, DeviceCapabilities, Device};
 
 /// A tracer device.
 ///
 /// A tracer is a device that pretty prints all packets traversing it
-/// using the provided writer function, and then passes them to another
+/// using the provided writer closure, and then passes them to another
 /// device.
-pub struct Tracer<D: for<'a> Device<'a>, P: PrettyPrint> {
+pub struct Tracer<D: for<'a> Device<'a>, P: PrettyPrint, W: FnMut(Instant, PrettyPrinter<P>)> {
     inner:  D,
-    writer: fn(u64, PrettyPrinter<P>),
+    writer: RefCell<W>,
 }
 
-impl<D: for<'a> Device<'a>, P: PrettyPrint> Tracer<D, P> {
-    /// Create a tracer device.
-    pub fn new(inner: D, writer: fn(timestamp: u64, printer: PrettyPrinter<P>)) -> Tracer<D, P> {
-        Tracer { inner, writer }
+impl<D: for<'a> Device<'a>, P: PrettyPrint, W: FnMut(Instant, PrettyPrinter<P>)> Tracer<D, P, W> {
+    /// Create a tracer device, taking a closure that prints the traced packets.
+    ///
+    /// Unlike a bare `fn` pointer, the closure may capture and mutate state,
+    /// e.g. to route traces to a buffered file handle or a packet counter.
+    /// A trivial `fn` pointer is still a valid closure, so this is a strict
+    /// superset of the previous interface.
+    pub fn new(inner: D, writer: W) -> Tracer<D, P, W> {
+        Tracer { inner, writer: RefCell::new(writer) }
     }
 
     /// Return the underlying device, consuming the tracer.
@@ -24,64 +32,67 @@ impl<D: for<'a> Device<'a>, P: PrettyPrint> Tracer<D, P> {
     }
 }
 
-impl<'a, D, P> Device<'a> for Tracer<D, P>
+impl<'a, D, P, W> Device<'a> for Tracer<D, P, W>
     where D: for<'b> Device<'b>,
           P: PrettyPrint + 'a,
+          W: FnMut(Instant, PrettyPrinter<P>) + 'a,
 {
-    type RxToken = RxToken<<D as Device<'a>>::RxToken, P>;
-    type TxToken = TxToken<<D as Device<'a>>::TxToken, P>;
+    type RxToken = RxToken<'a, <D as Device<'a>>::RxToken, P, W>;
+    type TxToken = TxToken<'a, <D as Device<'a>>::TxToken, P, W>;
 
     fn capabilities(&self) -> DeviceCapabilities { self.inner.capabilities() }
 
     fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
-        let &mut Self { ref mut inner, writer, .. } = self;
+        let &mut Self { ref mut inner, ref writer } = self;
         inner.receive().map(|(rx_token, tx_token)| {
-            let rx = RxToken { token: rx_token, writer: writer };
-            let tx = TxToken { token: tx_token, writer: writer };
+            let rx = RxToken { token: rx_token, writer };
+            let tx = TxToken { token: tx_token, writer };
             (rx, tx)
         })
     }
 
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        let &mut Self { ref mut inner, writer } = self;
+        let &mut Self { ref mut inner, ref writer } = self;
         inner.transmit().map(|tx_token| {
-            TxToken { token: tx_token, writer: writer }
+            TxToken { token: tx_token, writer }
         })
     }
 }
 
 #[doc(hidden)]
-pub struct RxToken<Rx: phy::RxToken, P: PrettyPrint> {
-    token:     Rx,
-    writer:    fn(u64, PrettyPrinter<P>)
+pub struct RxToken<'a, Rx: phy::RxToken, P: PrettyPrint, W: FnMut(Instant, PrettyPrinter<P>) + 'a> {
+    token:  Rx,
+    writer: &'a RefCell<W>,
 }
 
-impl<Rx: phy::RxToken, P: PrettyPrint> phy::RxToken for RxToken<Rx, P> {
-    fn consume<R, F>(self, timestamp: u64, f: F) -> Result<R>
+impl<'a, Rx: phy::RxToken, P: PrettyPrint, W: FnMut(Instant, PrettyPrinter<P>) + 'a> phy::RxToken
+        for RxToken<'a, Rx, P, W> {
+    fn consume<R, F>(self, timestamp: Instant, f: F) -> Result<R>
         where F: FnOnce(&[u8]) -> Result<R>
     {
         let Self { token, writer } = self;
         token.consume(timestamp, |buffer| {
-            writer(timestamp, PrettyPrinter::<P>::new("<- ", &buffer));
+            (writer.borrow_mut())(timestamp, PrettyPrinter::<P>::new("<- ", &buffer));
             f(buffer)
         })
     }
 }
 
 #[doc(hidden)]
-pub struct TxToken<Tx: phy::TxToken, P: PrettyPrint> {
-    token:     Tx,
-    writer:    fn(u64, PrettyPrinter<P>)
+pub struct TxToken<'a, Tx: phy::TxToken, P: PrettyPrint, W: FnMut(Instant, PrettyPrinter<P>) + 'a> {
+    token:  Tx,
+    writer: &'a RefCell<W>,
 }
 
-impl<Tx: phy::TxToken, P: PrettyPrint> phy::TxToken for TxToken<Tx, P> {
-    fn consume<R, F>(self, timestamp: u64, len: usize, f: F) -> Result<R>
+impl<'a, Tx: phy::TxToken, P: PrettyPrint, W: FnMut(Instant, PrettyPrinter<P>) + 'a> phy::TxToken
+        for TxToken<'a, Tx, P, W> {
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> Result<R>
         where F: FnOnce(&mut [u8]) -> Result<R>
     {
         let Self { token, writer } = self;
         token.consume(timestamp, len, |buffer| {
             let result = f(buffer);
-            writer(timestamp, PrettyPrinter::<P>::new("-> ", &buffer));
+            (writer.borrow_mut())(timestamp, PrettyPrinter::<P>::new("-> ", &buffer));
             result
         })
     }
@@ -0,0 +1,148 @@
+use core::{ops, fmt};
+
+/// A representation of an absolute time value.
+///
+/// The `Instant` type is a wrapper around a `u64` value that
+/// represents a number of milliseconds, monotonically increasing
+/// since an arbitrary moment in time, such as system startup. A value of
+/// `0` is inherently arbitrary.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    millis: u64,
+}
+
+impl Instant {
+    /// Create a new `Instant` from a number of milliseconds.
+    pub const fn from_millis(millis: u64) -> Instant {
+        Instant { millis }
+    }
+
+    /// The fractional number of milliseconds that have passed
+    /// since the beginning of time.
+    pub fn millis(&self) -> u64 {
+        self.millis % 1000
+    }
+
+    /// The number of whole seconds that have passed since the
+    /// beginning of time.
+    pub fn secs(&self) -> u64 {
+        self.millis / 1000
+    }
+
+    /// The total number of milliseconds that have passed since
+    /// the beginning of time.
+    pub fn total_millis(&self) -> u64 {
+        self.millis
+    }
+}
+
+impl fmt::Display for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:03}s", self.secs(), self.millis())
+    }
+}
+
+impl ops::Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant::from_millis(self.millis + rhs.total_millis())
+    }
+}
+
+impl ops::AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        self.millis += rhs.total_millis();
+    }
+}
+
+impl ops::Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        // Panics on underflow if `rhs` is later than `self`, since a
+        // `Duration` cannot be negative; callers comparing instants that
+        // may be out of order should guard with `>` first, as
+        // `fault_injector::Bucket::take` does.
+        Duration::from_millis(self.millis - rhs.millis)
+    }
+}
+
+impl ops::Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant::from_millis(self.millis - rhs.total_millis())
+    }
+}
+
+/// A relative amount of time.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Duration {
+    millis: u64,
+}
+
+impl Duration {
+    /// Create a new `Duration` from a number of milliseconds.
+    pub const fn from_millis(millis: u64) -> Duration {
+        Duration { millis }
+    }
+
+    /// Create a new `Duration` from a number of seconds.
+    pub const fn from_secs(secs: u64) -> Duration {
+        Duration { millis: secs * 1000 }
+    }
+
+    /// The total number of milliseconds.
+    pub fn total_millis(&self) -> u64 {
+        self.millis
+    }
+}
+
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:03}s", self.millis / 1000, self.millis % 1000)
+    }
+}
+
+impl ops::Add<Duration> for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        Duration::from_millis(self.millis + rhs.millis)
+    }
+}
+
+impl ops::Sub<Duration> for Duration {
+    type Output = Duration;
+
+    fn sub(self, rhs: Duration) -> Duration {
+        Duration::from_millis(self.millis - rhs.millis)
+    }
+}
+
+impl ops::Mul<u32> for Duration {
+    type Output = Duration;
+
+    fn mul(self, rhs: u32) -> Duration {
+        Duration::from_millis(self.millis * rhs as u64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_instant_ops() {
+        let t1 = Instant::from_millis(0);
+        let t2 = Instant::from_millis(1);
+        assert_eq!(t2 - t1, Duration::from_millis(1));
+        assert_eq!(t1 + Duration::from_millis(1), t2);
+    }
+
+    #[test]
+    fn test_duration_from_secs() {
+        assert_eq!(Duration::from_secs(1), Duration::from_millis(1000));
+    }
+}
@@ -0,0 +1,95 @@
+use core::fmt;
+
+use wire::EthernetAddress;
+
+/// A link-layer hardware address.
+///
+/// Neighbor discovery (ARP, NDP) is not limited to Ethernet; this enum lets
+/// the neighbor cache and interface layer stay generic over the address
+/// width and representation used by the underlying medium.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HardwareAddress {
+    Ethernet(EthernetAddress),
+    Ieee802154(Ieee802154Address),
+}
+
+impl HardwareAddress {
+    /// The broadcast hardware address, for the Ethernet representation.
+    ///
+    /// IEEE 802.15.4 has no single broadcast address shared with Ethernet,
+    /// so callers that need the broadcast address for a specific medium
+    /// should match on the variant they expect instead of relying on this
+    /// constant.
+    pub const BROADCAST: HardwareAddress = HardwareAddress::Ethernet(EthernetAddress::BROADCAST);
+
+    /// Query whether this address is a unicast address.
+    pub fn is_unicast(&self) -> bool {
+        match *self {
+            HardwareAddress::Ethernet(addr) => addr.is_unicast(),
+            HardwareAddress::Ieee802154(addr) => addr.is_unicast(),
+        }
+    }
+
+    /// Query whether this address is a broadcast address.
+    pub fn is_broadcast(&self) -> bool {
+        match *self {
+            HardwareAddress::Ethernet(addr) => addr.is_broadcast(),
+            HardwareAddress::Ieee802154(addr) => addr.is_broadcast(),
+        }
+    }
+}
+
+impl fmt::Display for HardwareAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HardwareAddress::Ethernet(addr) => write!(f, "{}", addr),
+            HardwareAddress::Ieee802154(addr) => write!(f, "{}", addr),
+        }
+    }
+}
+
+impl From<EthernetAddress> for HardwareAddress {
+    fn from(addr: EthernetAddress) -> HardwareAddress {
+        HardwareAddress::Ethernet(addr)
+    }
+}
+
+/// An eight-octet IEEE 802.15.4 extended address.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub struct Ieee802154Address(pub [u8; 8]);
+
+impl Ieee802154Address {
+    /// The broadcast address.
+    pub const BROADCAST: Ieee802154Address = Ieee802154Address([0xff; 8]);
+
+    /// Construct an IEEE 802.15.4 address from parts.
+    pub fn from_bytes(data: &[u8]) -> Ieee802154Address {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(data);
+        Ieee802154Address(bytes)
+    }
+
+    /// Return the address as a sequence of octets, in big-endian.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Query whether this address is the broadcast address.
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    /// Query whether this address is unicast.
+    pub fn is_unicast(&self) -> bool {
+        !self.is_broadcast()
+    }
+}
+
+impl fmt::Display for Ieee802154Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = self.0;
+        write!(f, "{:02x}-{:02x}-{:02x}-{:02x}-{:02x}-{:02x}-{:02x}-{:02x}",
+               bytes[0], bytes[1], bytes[2], bytes[3],
+               bytes[4], bytes[5], bytes[6], bytes[7])
+    }
+}